@@ -1,83 +1,225 @@
 use std::io;
 use std::env;
 use std::sync::Arc;
+use std::pin::Pin;
 use std::path::PathBuf;
+use std::future::Future;
+use std::time::Duration;
 use std::convert::TryFrom;
 use std::collections::HashMap;
 use std::fmt::{Debug, Display};
 
 use anyhow::{Error, Result};
 
+use base64::Engine;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use sha1::{Digest, Sha1};
+
 use tokio::net::{TcpListener, TcpStream};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, AsyncSeekExt};
 use tokio::signal;
 use tokio::fs::File;
+use tokio_util::io::ReaderStream;
 
-#[derive(Debug)]
-struct Request<'a> {
+#[derive(Debug, Clone)]
+struct Request {
     method: HttpMethod,
-    path: &'a str,
-    http_version: &'a str,
-    headers: HashMap<&'a str, HeaderValue<'a>>, 
-    body: &'a str,
+    path: String,
+    http_version: String,
+    headers: HashMap<String, HeaderValue>,
+    body: Vec<u8>,
+}
+
+/// A content coding negotiated with the client via `Accept-Encoding`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Brotli => "br",
+        }
+    }
+}
+
+/// A response body. Small, known-size payloads are buffered (`Sized`);
+/// large or incrementally-produced payloads are `Stream`ed as
+/// `Transfer-Encoding: chunked` instead of requiring a known length.
+enum Body {
+    Empty,
+    Sized(Vec<u8>),
+    Stream(Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>),
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(data: Vec<u8>) -> Self {
+        Body::Sized(data)
+    }
+}
+
+fn compress_body(encoding: Encoding, body: &[u8]) -> io::Result<Vec<u8>> {
+    use std::io::Write;
+
+    match encoding {
+        Encoding::Gzip => {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(body)?;
+            encoder.finish()
+        }
+        Encoding::Brotli => {
+            let mut output = Vec::new();
+            let mut writer = brotli::CompressorWriter::new(&mut output, 4096, 5, 22);
+            writer.write_all(body)?;
+            drop(writer);
+            Ok(output)
+        }
+    }
 }
 
 struct Response {
     status_code: HttpStatusCode,
     headers: Vec<(String, String)>,
-    body: String,
+    body: Body,
+    content_encoding: Option<Encoding>,
 }
 
 impl Response {
-    pub fn new(status_code: HttpStatusCode, headers: Vec<(String, String)>, body: String) -> Self {
+    pub fn new(status_code: HttpStatusCode, headers: Vec<(String, String)>, body: impl Into<Body>) -> Self {
         Response {
             status_code,
             headers,
-            body,
+            body: body.into(),
+            content_encoding: None,
         }
     }
     pub fn new_without_body(status_code: HttpStatusCode, headers: Vec<(String, String)>) -> Self {
         Response {
             status_code,
             headers,
-            body: String::with_capacity(0),
+            body: Body::Empty,
+            content_encoding: None,
         }
     }
-    pub async fn write_to<W>(&self, w: &mut W) -> io::Result<usize>
-    where 
-        W: AsyncWriteExt + Unpin
+    /// Builds a response whose body is produced incrementally and written
+    /// out as `Transfer-Encoding: chunked`, never buffered in full.
+    pub fn new_streamed<S>(status_code: HttpStatusCode, headers: Vec<(String, String)>, stream: S) -> Self
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + 'static,
     {
-        let status_code_int = usize::from(self.status_code);
-        let mut buf = format!("HTTP/1.1 {status_code_int} {}\r\n", self.status_code);
+        Response {
+            status_code,
+            headers,
+            body: Body::Stream(Box::pin(stream)),
+            content_encoding: None,
+        }
+    }
+
+    /// Writes the status line and all headers except `Content-Length` and
+    /// `Transfer-Encoding`, which the two body-writing strategies below emit
+    /// themselves once they know which one applies.
+    fn write_status_and_headers(status_code: HttpStatusCode, headers: &[(String, String)], content_encoding: Option<Encoding>, buf: &mut Vec<u8>) {
+        let status_code_int = usize::from(status_code);
+        buf.extend_from_slice(format!("HTTP/1.1 {status_code_int} {status_code}\r\n").as_bytes());
 
-        for (key, value) in &self.headers {
-            buf += key;
-            buf += ": ";
-            buf += value;
-            buf += "\r\n";
+        for (key, value) in headers {
+            if key.eq_ignore_ascii_case("Content-Length") || key.eq_ignore_ascii_case("Transfer-Encoding") {
+                continue;
+            }
+            buf.extend_from_slice(key.as_bytes());
+            buf.extend_from_slice(b": ");
+            buf.extend_from_slice(value.as_bytes());
+            buf.extend_from_slice(b"\r\n");
         }
 
-        buf += "\r\n";
+        if let Some(encoding) = content_encoding {
+            buf.extend_from_slice(format!("Content-Encoding: {}\r\n", encoding.as_str()).as_bytes());
+        }
+    }
 
-        buf += &self.body;
+    pub async fn write_to<W>(self, w: &mut W) -> io::Result<usize>
+    where
+        W: AsyncWriteExt + Unpin
+    {
+        match self.body {
+            Body::Stream(stream) => {
+                let mut buf = Vec::new();
+                Self::write_status_and_headers(self.status_code, &self.headers, self.content_encoding, &mut buf);
+                buf.extend_from_slice(b"Transfer-Encoding: chunked\r\n\r\n");
 
-        w.write(buf.as_bytes()).await
+                // `write` may perform a short write, which would desync the
+                // chunk-size prefix from the bytes actually sent - every
+                // write here must be a `write_all`.
+                w.write_all(&buf).await?;
+                let mut written = buf.len();
+
+                let mut stream = stream;
+                while let Some(chunk) = stream.next().await {
+                    let chunk = chunk?;
+                    let size_line = format!("{:x}\r\n", chunk.len());
+
+                    w.write_all(size_line.as_bytes()).await?;
+                    w.write_all(&chunk).await?;
+                    w.write_all(b"\r\n").await?;
+
+                    written += size_line.len() + chunk.len() + 2;
+                }
+                w.write_all(b"0\r\n\r\n").await?;
+                written += 5;
+
+                Ok(written)
+            }
+            Body::Empty | Body::Sized(_) => {
+                let data = match self.body {
+                    Body::Sized(data) => data,
+                    _ => Vec::new(),
+                };
+                let body = match self.content_encoding {
+                    Some(encoding) => compress_body(encoding, &data)?,
+                    None => data,
+                };
+
+                let mut buf = Vec::new();
+                Self::write_status_and_headers(self.status_code, &self.headers, self.content_encoding, &mut buf);
+                buf.extend_from_slice(format!("Content-Length: {}\r\n", body.len()).as_bytes());
+
+                buf.extend_from_slice(b"\r\n");
+                buf.extend_from_slice(&body);
+
+                w.write_all(&buf).await?;
+                Ok(buf.len())
+            }
+        }
     }
 }
 
 impl From<HttpStatusCode> for Response {
     fn from(sc: HttpStatusCode) -> Self {
-        Response::new(sc, Vec::with_capacity(0), String::with_capacity(0))
+        Response::new_without_body(sc, Vec::with_capacity(0))
     }
 }
 
-#[derive(Debug, PartialEq)]
-enum HeaderValue<'a> {
-    Single(&'a str),
-    Multiple(Vec<&'a str>),
+#[derive(Debug, Clone, PartialEq)]
+enum HeaderValue {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl HeaderValue {
+    /// Returns the first value, which is the only value for most headers.
+    fn first(&self) -> &str {
+        match self {
+            HeaderValue::Single(s) => s,
+            HeaderValue::Multiple(v) => &v[0],
+        }
+    }
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 enum HttpMethod {
     GET,
     POST,
@@ -98,11 +240,19 @@ impl Display for HttpMethod {
 
 #[derive(Debug, Clone, Copy)]
 enum HttpStatusCode {
+    SwitchingProtocols,
+
     Ok,
     Created,
+    NoContent,
+
+    NotModified,
+    PartialContent,
 
     BadRequest,
     NotFound,
+    RequestTimeout,
+    RangeNotSatisfiable,
 
     InternalServerError,
 }
@@ -110,10 +260,16 @@ enum HttpStatusCode {
 impl From<HttpStatusCode> for usize {
     fn from(sc: HttpStatusCode) -> usize {
         match sc {
+            HttpStatusCode::SwitchingProtocols => 101,
             HttpStatusCode::Ok => 200,
             HttpStatusCode::Created => 201,
+            HttpStatusCode::NoContent => 204,
+            HttpStatusCode::NotModified => 304,
+            HttpStatusCode::PartialContent => 206,
             HttpStatusCode::BadRequest => 400,
             HttpStatusCode::NotFound => 404,
+            HttpStatusCode::RequestTimeout => 408,
+            HttpStatusCode::RangeNotSatisfiable => 416,
             HttpStatusCode::InternalServerError => 500,
         }
     }
@@ -122,10 +278,16 @@ impl From<HttpStatusCode> for usize {
 impl Display for HttpStatusCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let str = match self {
+            Self::SwitchingProtocols => "Switching Protocols",
             Self::Ok => "OK",
             Self::Created => "Created",
+            Self::NoContent => "No Content",
+            Self::NotModified => "Not Modified",
+            Self::PartialContent => "Partial Content",
             Self::BadRequest => "Bad Request",
             Self::NotFound => "Not Found",
+            Self::RequestTimeout => "Request Timeout",
+            Self::RangeNotSatisfiable => "Range Not Satisfiable",
             Self::InternalServerError => "Internal Server Error",
         };
 
@@ -149,50 +311,49 @@ impl TryFrom<&str> for HttpMethod {
             "CONNECT" => Ok(HttpMethod::CONNECT),
             _ => Err(()),
         }
-    } 
+    }
 }
 
 #[derive(Debug)]
-enum RouteError {
-    NoMatch,
-    Error(Error),
-}
+struct RouteError;
 
 impl std::error::Error for RouteError {}
 
 impl Display for RouteError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            RouteError::NoMatch => write!(f, "Route did not match Path!"),
-            RouteError::Error(e) => write!(f, "Internal Error: {e}"),
-        }
+        write!(f, "Route did not match Path!")
     }
 }
 
-struct RouteDefinition<T, M, A>
-where 
+struct RouteDefinition<T, M, A, Fut>
+where
     M: Fn(&str) -> Option<T>,
-    A: Fn(Request, T) -> Result<Response>,
+    A: Fn(Request, T) -> Fut,
+    Fut: Future<Output = Result<Response>>,
 {
     matches: M,
     action: A,
 }
 
+type RouteFuture = Pin<Box<dyn Future<Output = Result<Response>> + Send>>;
+
 struct Route
 {
-    run: Box<dyn Fn(Request<'_>) -> Result<Response>>,
+    run: Box<dyn Fn(Request) -> RouteFuture + Send + Sync>,
 }
 
-impl<T, M, A> From<RouteDefinition<T, M, A>> for Route
+impl<T, M, A, Fut> From<RouteDefinition<T, M, A, Fut>> for Route
 where
-    M: Fn(&str) -> Option<T> + 'static,
-    A: Fn(Request, T) -> Result<Response> + 'static,
+    T: 'static,
+    M: Fn(&str) -> Option<T> + Send + Sync + 'static,
+    A: Fn(Request, T) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Response>> + Send + 'static,
 {
-    fn from(definition: RouteDefinition<T, M, A>) -> Self {
-        let run = Box::new(move |request: Request| {
-            match (definition.matches)(request.path) {
-                Some(matches) => (definition.action)(request, matches),
-                None => Err(Error::new(RouteError::NoMatch)),
+    fn from(definition: RouteDefinition<T, M, A, Fut>) -> Self {
+        let run = Box::new(move |request: Request| -> RouteFuture {
+            match (definition.matches)(&request.path) {
+                Some(matches) => Box::pin((definition.action)(request, matches)),
+                None => Box::pin(async { Err(Error::new(RouteError)) }),
             }
         });
 
@@ -200,165 +361,1158 @@ where
     }
 }
 
-fn split_header(header: &str) -> Option<(&str, &str)> {
-    let mut iter = header.splitn(2, ':');
+struct WsRouteDefinition<T, M, A, Fut>
+where
+    M: Fn(&str) -> Option<T>,
+    A: Fn(WebSocket, T) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    matches: M,
+    action: A,
+}
 
-    let key = iter.next()?; 
-    let value = iter.next()?.trim_start();
+type WsFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+type WsRunFn = Box<dyn Fn(&str, WebSocket) -> WsFuture + Send + Sync>;
 
-    Some((key, value))
+/// Like `Route`, but the handler takes ownership of the upgraded connection
+/// instead of returning a `Response`. `matches` is kept separate from `run`
+/// so the router can check for a match before performing the handshake,
+/// which has to happen before a `WebSocket` exists at all.
+struct WsRoute {
+    matches: Box<dyn Fn(&str) -> bool + Send + Sync>,
+    run: WsRunFn,
+}
+
+impl<T, M, A, Fut> From<WsRouteDefinition<T, M, A, Fut>> for WsRoute
+where
+    T: 'static,
+    M: Fn(&str) -> Option<T> + Send + Sync + 'static,
+    A: Fn(WebSocket, T) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    fn from(definition: WsRouteDefinition<T, M, A, Fut>) -> Self {
+        let WsRouteDefinition { matches, action } = definition;
+        let matches = Arc::new(matches);
+        let run_matches = Arc::clone(&matches);
+
+        Self {
+            matches: Box::new(move |path| matches(path).is_some()),
+            run: Box::new(move |path, ws| match run_matches(path) {
+                Some(captures) => Box::pin(action(ws, captures)),
+                None => Box::pin(async { Err(Error::msg("WebSocket route did not match Path!")) }),
+            }),
+        }
+    }
 }
 
-async fn read_to_string<R: AsyncReadExt + std::marker::Unpin>(stream: &mut R) -> Result<String> {
-    const BUFFER_SIZE : usize = 1024;
+/// Matches `path` against a route `pattern`.
+///
+/// Segments of `pattern` starting with `:` capture the corresponding path
+/// segment under that name. A trailing segment of the form `:name*` captures
+/// the remainder of the path (including any `/`) instead of a single
+/// segment, which is what lets `/files/:filename*` and `/echo/:message*`
+/// accept captures containing slashes.
+fn match_pattern(pattern: &'static str, path: &str) -> Option<HashMap<&'static str, String>> {
+    let pattern_segments: Vec<&'static str> = pattern.split('/').collect();
+    let path_segments: Vec<&str> = path.split('/').collect();
 
-    let mut buf = [0 as u8; 1024];
-    let mut vec = Vec::new();
+    let mut captures = HashMap::new();
 
-    loop {
-        match stream.read(&mut buf).await {
-            Ok(n) => {
-                vec.extend_from_slice(&buf[..n]);
-                if n < BUFFER_SIZE {
-                    return Ok(String::from_utf8(vec)?);
-                }
+    for (i, pattern_segment) in pattern_segments.iter().enumerate() {
+        if let Some(name) = pattern_segment.strip_prefix(':') {
+            if let Some(name) = name.strip_suffix('*') {
+                let rest = path_segments.get(i..)?;
+                captures.insert(name, rest.join("/"));
+                return Some(captures);
             }
-            Err(e) => {
-                return Err(e.into());
+
+            let segment = path_segments.get(i)?;
+            captures.insert(name, segment.to_string());
+        } else if path_segments.get(i) != Some(pattern_segment) {
+            return None;
+        }
+    }
+
+    if path_segments.len() != pattern_segments.len() {
+        return None;
+    }
+
+    Some(captures)
+}
+
+#[cfg(test)]
+mod match_pattern_tests {
+    use super::match_pattern;
+
+    #[test]
+    fn matches_static_path() {
+        assert!(match_pattern("/user-agent", "/user-agent").is_some());
+        assert!(match_pattern("/user-agent", "/other").is_none());
+    }
+
+    #[test]
+    fn captures_single_segment() {
+        let captures = match_pattern("/echo/:message", "/echo/hello").unwrap();
+        assert_eq!(captures.get("message"), Some(&"hello".to_string()));
+    }
+
+    #[test]
+    fn single_segment_capture_rejects_extra_segments() {
+        assert!(match_pattern("/echo/:message", "/echo/hello/world").is_none());
+    }
+
+    #[test]
+    fn trailing_wildcard_captures_remaining_slashes() {
+        let captures = match_pattern("/files/:filename*", "/files/a/b/c.txt").unwrap();
+        assert_eq!(captures.get("filename"), Some(&"a/b/c.txt".to_string()));
+    }
+
+    #[test]
+    fn trailing_wildcard_captures_empty_remainder() {
+        let captures = match_pattern("/files/:filename*", "/files").unwrap();
+        assert_eq!(captures.get("filename"), Some(&"".to_string()));
+    }
+
+    #[test]
+    fn rejects_wrong_segment_count() {
+        assert!(match_pattern("/a/b", "/a").is_none());
+        assert!(match_pattern("/a/b", "/a/b/c").is_none());
+    }
+}
+
+/// Holds the registered routes and dispatches requests to the first one
+/// whose method and path pattern match.
+struct Router {
+    routes: Vec<(HttpMethod, Route)>,
+    ws_routes: Vec<WsRoute>,
+    cors: CorsConfig,
+}
+
+impl Router {
+    fn new(cors: CorsConfig) -> Self {
+        Router { routes: Vec::new(), ws_routes: Vec::new(), cors }
+    }
+
+    fn add<T, M, A, Fut>(&mut self, method: HttpMethod, definition: RouteDefinition<T, M, A, Fut>)
+    where
+        T: 'static,
+        M: Fn(&str) -> Option<T> + Send + Sync + 'static,
+        A: Fn(Request, T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Response>> + Send + 'static,
+    {
+        self.routes.push((method, Route::from(definition)));
+    }
+
+    fn add_ws<T, M, A, Fut>(&mut self, definition: WsRouteDefinition<T, M, A, Fut>)
+    where
+        T: 'static,
+        M: Fn(&str) -> Option<T> + Send + Sync + 'static,
+        A: Fn(WebSocket, T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.ws_routes.push(WsRoute::from(definition));
+    }
+
+    /// Finds the WebSocket route, if any, whose pattern matches `path`.
+    /// WebSocket upgrades are always `GET` requests, so unlike `dispatch`
+    /// there is no method to filter on.
+    fn find_ws_route(&self, path: &str) -> Option<&WsRoute> {
+        self.ws_routes.iter().find(|route| (route.matches)(path))
+    }
+
+    async fn dispatch(&self, request: Request) -> Response {
+        let accept_encoding = request.headers.get("Accept-Encoding").cloned();
+        let origin = request.headers.get("Origin").map(|v| v.first().to_string());
+
+        if request.method == HttpMethod::OPTIONS && request.headers.contains_key("Access-Control-Request-Method") {
+            return self.cors.preflight_response(origin.as_deref());
+        }
+
+        let mut response = 'matched: {
+            for (method, route) in &self.routes {
+                if *method != request.method {
+                    continue;
+                }
+
+                match (route.run)(request.clone()).await {
+                    Ok(response) => break 'matched response,
+                    Err(e) if e.is::<RouteError>() => continue,
+                    Err(e) => {
+                        eprintln!("Error handling request: {e}");
+                        break 'matched Response::from(HttpStatusCode::InternalServerError);
+                    }
+                }
             }
+
+            Response::from(HttpStatusCode::NotFound)
+        };
+
+        if let Some(origin) = origin.as_deref() {
+            self.cors.apply(origin, &mut response);
+        }
+
+        negotiate_compression(accept_encoding.as_ref(), &mut response);
+        response
+    }
+}
+
+/// Cross-Origin Resource Sharing policy applied during dispatch: actual
+/// requests get `Access-Control-Allow-Origin` (and `-Credentials`) echoed
+/// onto their response, while `OPTIONS` preflights are short-circuited
+/// before routing.
+struct CorsConfig {
+    allowed_origins: Vec<String>,
+    allowed_methods: Vec<HttpMethod>,
+    allowed_headers: Vec<String>,
+    max_age: Duration,
+    allow_credentials: bool,
+}
+
+impl CorsConfig {
+    fn matches_origin(&self, origin: &str) -> bool {
+        self.allowed_origins.iter().any(|allowed| allowed == "*" || allowed == origin)
+    }
+
+    /// Adds the CORS response headers for an actual (non-preflight) request,
+    /// echoing back `origin` itself rather than `*` or the whole allow-list,
+    /// since a wildcard can't be combined with credentialed requests.
+    fn apply(&self, origin: &str, response: &mut Response) {
+        if !self.matches_origin(origin) {
+            return;
+        }
+
+        response.headers.push(("Access-Control-Allow-Origin".to_string(), origin.to_string()));
+        if self.allow_credentials {
+            response.headers.push(("Access-Control-Allow-Credentials".to_string(), "true".to_string()));
+        }
+    }
+
+    /// Builds the `204 No Content` response to an `OPTIONS` preflight,
+    /// carrying the allowed methods, headers, and cache lifetime. Falls back
+    /// to a bare `204` with no CORS headers if `origin` isn't allowed.
+    fn preflight_response(&self, origin: Option<&str>) -> Response {
+        let Some(origin) = origin.filter(|origin| self.matches_origin(origin)) else {
+            return Response::from(HttpStatusCode::NoContent);
+        };
+
+        let methods = self.allowed_methods.iter()
+            .map(HttpMethod::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut headers = vec![
+            ("Access-Control-Allow-Origin".to_string(), origin.to_string()),
+            ("Access-Control-Allow-Methods".to_string(), methods),
+            ("Access-Control-Allow-Headers".to_string(), self.allowed_headers.join(", ")),
+            ("Access-Control-Max-Age".to_string(), self.max_age.as_secs().to_string()),
+        ];
+
+        if self.allow_credentials {
+            headers.push(("Access-Control-Allow-Credentials".to_string(), "true".to_string()));
+        }
+
+        Response::new_without_body(HttpStatusCode::NoContent, headers)
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: vec!["*".to_string()],
+            allowed_methods: vec![
+                HttpMethod::GET,
+                HttpMethod::POST,
+                HttpMethod::PUT,
+                HttpMethod::PATCH,
+                HttpMethod::DELETE,
+            ],
+            allowed_headers: vec!["Content-Type".to_string()],
+            max_age: Duration::from_secs(86400),
+            allow_credentials: false,
         }
     }
 }
 
-async fn send_file(stream: &mut TcpStream, path: &str, dir: &str) -> io::Result<u64> {
-    let file_name = path.strip_prefix("/files/").unwrap();
-    let absolute_path = format!("{dir}{file_name}");
-    
-    let file_path = PathBuf::from(absolute_path);
-
-    let mut file = File::open(file_path).await?;
-    let size = file.metadata().await?.len();
-    
-    let response = Response::new_without_body(
-        HttpStatusCode::Ok, 
-        vec![
-            ("Content-Type".to_string(), "application/octet-stream".to_string()),
-            ("Content-Length".to_string(), format!("{size}"))
-        ],
-    );
-
-    response.write_to(stream).await?;
-    tokio::io::copy(&mut file, stream).await
-}
-
-async fn save_file(stream: &mut TcpStream, path: &str, dir: &str, body: String) -> io::Result<usize> {
-    let file_name = path.strip_prefix("/files/").unwrap();
-    let absolute_path = format!("{dir}{file_name}");
-    
-    let file_path = PathBuf::from(absolute_path);
-
-    let mut file = File::open(file_path).await?;
-    let res = file.write(body.as_bytes()).await;
-    Response::from(res
-        .as_ref()
-        .map_or_else(|_e| HttpStatusCode::InternalServerError, |_v| HttpStatusCode::Ok)
-    ).write_to(stream).await?;
-        
-    res
-}
-
-async fn handle_connection(mut stream: TcpStream, dir: Arc<Option<String>>) {
-    match read_to_string(&mut stream).await {
-        Ok(buf) => {
-            let mut lines = buf.lines();
-            let header_line = lines.next()
-                .and_then(|line| line.find(' ').and_then(|i| Some((line, i))))
-                .and_then(|(line, idx1)| match (idx1, line.rfind(' ')) {
-                    (idx1, Some(idx2)) if idx1 != idx2 => Some((*&line[..idx1].trim(), *&line[idx1+1..idx2].trim(), *&line[idx2+1..].trim())),
-                    (_, _) => None,
-                });
-            let (method, path, version) = match header_line {
-                Some(t) => t,
-                None => {
-                    println!("Invalid Request");
-                    Response::from(HttpStatusCode::BadRequest).write_to(&mut stream).await;
-                    panic!("");
+#[cfg(test)]
+mod cors_tests {
+    use super::{CorsConfig, HttpStatusCode};
+
+    fn header<'a>(response: &'a super::Response, name: &str) -> Option<&'a str> {
+        response.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn wildcard_matches_any_origin() {
+        let cors = CorsConfig::default();
+        assert!(cors.matches_origin("https://example.com"));
+        assert!(cors.matches_origin("https://anything.test"));
+    }
+
+    #[test]
+    fn explicit_allow_list_rejects_other_origins() {
+        let cors = CorsConfig { allowed_origins: vec!["https://example.com".to_string()], ..CorsConfig::default() };
+        assert!(cors.matches_origin("https://example.com"));
+        assert!(!cors.matches_origin("https://evil.test"));
+    }
+
+    #[test]
+    fn preflight_echoes_matched_origin_and_config() {
+        let cors = CorsConfig::default();
+        let response = cors.preflight_response(Some("https://example.com"));
+
+        assert_eq!(usize::from(response.status_code), 204);
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), Some("https://example.com"));
+        assert_eq!(header(&response, "Access-Control-Allow-Methods"), Some("GET, POST, PUT, PATCH, DELETE"));
+        assert_eq!(header(&response, "Access-Control-Allow-Headers"), Some("Content-Type"));
+        assert_eq!(header(&response, "Access-Control-Max-Age"), Some("86400"));
+    }
+
+    #[test]
+    fn preflight_omits_cors_headers_for_disallowed_origin() {
+        let cors = CorsConfig { allowed_origins: vec!["https://example.com".to_string()], ..CorsConfig::default() };
+        let response = cors.preflight_response(Some("https://evil.test"));
+
+        assert_eq!(usize::from(response.status_code), 204);
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), None);
+    }
+
+    #[test]
+    fn preflight_with_no_origin_header_is_a_bare_no_content() {
+        let cors = CorsConfig::default();
+        let response = cors.preflight_response(None);
+
+        assert_eq!(usize::from(response.status_code), usize::from(HttpStatusCode::NoContent));
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), None);
+    }
+
+    #[test]
+    fn credentials_never_echo_wildcard() {
+        let cors = CorsConfig { allow_credentials: true, ..CorsConfig::default() };
+        let response = cors.preflight_response(Some("https://example.com"));
+
+        assert_eq!(header(&response, "Access-Control-Allow-Origin"), Some("https://example.com"));
+        assert_eq!(header(&response, "Access-Control-Allow-Credentials"), Some("true"));
+    }
+}
+
+/// Picks the content coding that `Response::write_to` will compress
+/// `response`'s body with: brotli if `Accept-Encoding` offers `br`, else gzip
+/// if it offers `gzip`; `q` weights are ignored. The body is left uncompressed
+/// when it is empty or is a small `application/octet-stream` payload (where
+/// compression overhead outweighs the benefit).
+fn negotiate_compression(accept_encoding: Option<&HeaderValue>, response: &mut Response) {
+    const COMPRESSIBLE_SIZE_THRESHOLD: usize = 256;
+
+    // A partial body isn't valid input to a standalone decoder, and a 304
+    // has no body to compress - leave both alone, the same way Apache and
+    // Nginx disable gzip on Range requests.
+    if matches!(response.status_code, HttpStatusCode::PartialContent | HttpStatusCode::NotModified) {
+        return;
+    }
+
+    // Streamed bodies are never buffered for compression.
+    let Body::Sized(body) = &response.body else {
+        return;
+    };
+
+    if body.is_empty() {
+        return;
+    }
+
+    let is_octet_stream = response.headers.iter()
+        .any(|(key, value)| key.eq_ignore_ascii_case("Content-Type") && value == "application/octet-stream");
+    if is_octet_stream && body.len() < COMPRESSIBLE_SIZE_THRESHOLD {
+        return;
+    }
+
+    let Some(accept_encoding) = accept_encoding else {
+        return;
+    };
+
+    // Strip any `;q=...` weighting before comparing - real clients (curl,
+    // browsers) send codings like `gzip;q=1.0, br;q=0.9`, not bare tokens.
+    let offered: Vec<&str> = accept_encoding.first().split(',')
+        .map(|token| token.split(';').next().unwrap_or(token).trim())
+        .collect();
+
+    response.content_encoding = if offered.iter().any(|e| e.eq_ignore_ascii_case("br")) {
+        Some(Encoding::Brotli)
+    } else if offered.iter().any(|e| e.eq_ignore_ascii_case("gzip")) {
+        Some(Encoding::Gzip)
+    } else {
+        None
+    };
+}
+
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// A message exchanged over a `WebSocket`. Ping/pong bookkeeping is handled
+/// internally by `WebSocket::recv`, so only `Text`, `Binary`, and the
+/// implicit end-of-stream (`recv` returning `None`) are ever seen by a
+/// handler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum WsMessage {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// An upgraded connection speaking the RFC 6455 WebSocket framing protocol.
+/// Fragmented messages (continuation frames) are not reassembled - only the
+/// handful of opcodes a minimal client/server exchange needs are supported.
+struct WebSocket {
+    stream: TcpStream,
+}
+
+impl WebSocket {
+    const OPCODE_TEXT: u8 = 0x1;
+    const OPCODE_BINARY: u8 = 0x2;
+    const OPCODE_CLOSE: u8 = 0x8;
+    const OPCODE_PING: u8 = 0x9;
+    const OPCODE_PONG: u8 = 0xA;
+
+    /// Frames announcing a payload larger than this are rejected before the
+    /// receive buffer is allocated, so a forged extended length can't be
+    /// used to force an allocator abort.
+    const MAX_FRAME_LEN: u64 = 16 * 1024 * 1024;
+
+    /// Reads the next `Text`/`Binary` message, transparently answering pings
+    /// with a pong and skipping pongs. Returns `Ok(None)` once the peer has
+    /// sent a close frame, having already echoed it back as RFC 6455
+    /// requires.
+    async fn recv(&mut self) -> Result<Option<WsMessage>> {
+        loop {
+            let (opcode, payload) = self.read_frame().await?;
+
+            match opcode {
+                Self::OPCODE_TEXT => return Ok(Some(WsMessage::Text(String::from_utf8(payload)?))),
+                Self::OPCODE_BINARY => return Ok(Some(WsMessage::Binary(payload))),
+                Self::OPCODE_PING => self.write_frame(Self::OPCODE_PONG, &payload).await?,
+                Self::OPCODE_PONG => continue,
+                Self::OPCODE_CLOSE => {
+                    self.write_frame(Self::OPCODE_CLOSE, &payload).await?;
+                    return Ok(None);
                 }
-            };
+                _ => continue,
+            }
+        }
+    }
 
-            let method = HttpMethod::try_from(method).expect("Error parsing HTTP Method");
+    async fn send(&mut self, message: WsMessage) -> Result<()> {
+        let (opcode, payload) = match message {
+            WsMessage::Text(text) => (Self::OPCODE_TEXT, text.into_bytes()),
+            WsMessage::Binary(data) => (Self::OPCODE_BINARY, data),
+        };
+
+        self.write_frame(opcode, &payload).await
+    }
+
+    /// Reads one frame's 2-byte header, its 7-bit/16-bit/64-bit extended
+    /// length, its masking key (client frames are always masked), and its
+    /// payload, unmasking the payload in place.
+    async fn read_frame(&mut self) -> Result<(u8, Vec<u8>)> {
+        let mut header = [0u8; 2];
+        self.stream.read_exact(&mut header).await?;
+
+        let opcode = header[0] & 0x0F;
+        let masked = header[1] & 0x80 != 0;
+        let mut len = (header[1] & 0x7F) as u64;
+
+        if len == 126 {
+            let mut extended = [0u8; 2];
+            self.stream.read_exact(&mut extended).await?;
+            len = u16::from_be_bytes(extended) as u64;
+        } else if len == 127 {
+            let mut extended = [0u8; 8];
+            self.stream.read_exact(&mut extended).await?;
+            len = u64::from_be_bytes(extended);
+        }
+
+        // The length above is client-supplied; reject it before allocating
+        // so an oversized frame can't be used to abort the whole process.
+        if len > Self::MAX_FRAME_LEN {
+            return Err(Error::msg("WebSocket frame payload too large"));
+        }
 
-            println!("Method: {method}, Path: {path}, Version: {version}");
+        let mut mask = [0u8; 4];
+        if masked {
+            self.stream.read_exact(&mut mask).await?;
+        }
 
-            let headers = lines.by_ref().take_while(|line| !line.is_empty());
-            let headers : Vec<(&str, &str)> = headers.filter_map(split_header).collect();
+        let mut payload = vec![0u8; len as usize];
+        self.stream.read_exact(&mut payload).await?;
 
-            let mut body = lines.fold(String::new(), |a, b| a + b + "\n"); 
-            if !body.is_empty() {
-                // NOTE: Remove last newline that is inserted by the fold.
-                body.truncate(body.len() - 1);
+        if masked {
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
             }
+        }
 
-            match path {
-                "/" => {
-                    Response::from(HttpStatusCode::Ok).write_to(&mut stream).await;
-                },
-                "/user-agent" => {
-                    match headers.iter().find(|(key, _)| *key == "User-Agent") {
-                        Some((_, user_agent)) => {
-                            let len = format!("{}", user_agent.len());
-
-                            let response = Response::new(
-                                HttpStatusCode::Ok,
-                                vec![
-                                    ("Content-Type".to_string(), "text/plain".to_string()),
-                                    ("Content-Length".to_string(), len),
-                                ],
-                                user_agent.to_string()
-                            );
-
-                            response.write_to(&mut stream).await;
-                        }
-                        None => {
-                            Response::from(HttpStatusCode::NotFound).write_to(&mut stream).await;
-                        }
+        Ok((opcode, payload))
+    }
+
+    /// Writes one unmasked frame - RFC 6455 requires servers to never mask
+    /// the frames they send.
+    async fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 10);
+        frame.push(0x80 | opcode);
+
+        let len = payload.len();
+        if len <= 125 {
+            frame.push(len as u8);
+        } else if len <= u16::MAX as usize {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+
+        frame.extend_from_slice(payload);
+        self.stream.write_all(&frame).await?;
+
+        Ok(())
+    }
+}
+
+/// Computes the `Sec-WebSocket-Accept` value for a client's
+/// `Sec-WebSocket-Key`, per RFC 6455: base64(SHA-1(key + GUID)).
+fn websocket_accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(WEBSOCKET_GUID.as_bytes());
+
+    base64::engine::general_purpose::STANDARD.encode(hasher.finalize())
+}
+
+/// A WebSocket upgrade request needs `Upgrade: websocket`, `Upgrade` in the
+/// (possibly comma-separated) `Connection` header, and a `Sec-WebSocket-Key`.
+fn is_websocket_upgrade(request: &Request) -> bool {
+    let has_upgrade = request.headers.get("Upgrade")
+        .is_some_and(|v| v.first().eq_ignore_ascii_case("websocket"));
+    let has_connection_upgrade = request.headers.get("Connection")
+        .is_some_and(|v| v.first().split(',').any(|token| token.trim().eq_ignore_ascii_case("Upgrade")));
+
+    has_upgrade && has_connection_upgrade && request.headers.contains_key("Sec-WebSocket-Key")
+}
+
+fn split_header(header: &str) -> Option<(&str, &str)> {
+    let mut iter = header.splitn(2, ':');
+
+    let key = iter.next()?;
+    let value = iter.next()?.trim_start();
+
+    Some((key, value))
+}
+
+fn headers_to_map(headers: Vec<(&str, &str)>) -> HashMap<String, HeaderValue> {
+    let mut map: HashMap<String, HeaderValue> = HashMap::new();
+
+    for (key, value) in headers {
+        map.entry(key.to_string())
+            .and_modify(|existing| {
+                let merged = match existing {
+                    HeaderValue::Single(first) => HeaderValue::Multiple(vec![first.clone(), value.to_string()]),
+                    HeaderValue::Multiple(values) => {
+                        values.push(value.to_string());
+                        return;
                     }
-                }
-                _ if path.starts_with("/echo/") => {
-                    let message = path.strip_prefix("/echo/").unwrap();
-                    let len = format!("{}", message.len());
+                };
+                *existing = merged;
+            })
+            .or_insert(HeaderValue::Single(value.to_string()));
+    }
+
+    map
+}
+
+#[derive(Debug)]
+enum ReadRequestError {
+    Io(io::Error),
+    ConnectionClosed,
+    /// No bytes for a new request arrived within the idle timeout; the
+    /// connection should simply be dropped, not answered.
+    IdleTimeout,
+    /// The idle timeout fired after a request had already started arriving.
+    RequestTimeout,
+    BadRequest(&'static str),
+}
+
+impl Display for ReadRequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadRequestError::Io(e) => write!(f, "I/O error: {e}"),
+            ReadRequestError::ConnectionClosed => write!(f, "Connection closed by peer"),
+            ReadRequestError::IdleTimeout => write!(f, "Idle timeout waiting for a request"),
+            ReadRequestError::RequestTimeout => write!(f, "Timed out mid-request"),
+            ReadRequestError::BadRequest(reason) => write!(f, "Bad Request: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ReadRequestError {}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Reads a single request off `stream`.
+///
+/// Bytes are accumulated until the `\r\n\r\n` header terminator is found, the
+/// request line and headers are parsed out of that prefix, and then - if a
+/// `Content-Length` header is present - exactly that many body bytes are
+/// read, reusing whatever was already buffered past the terminator. Reads
+/// are bounded by `idle_timeout`; if it fires before any bytes of a new
+/// request have arrived the connection is simply idle, but if it fires once
+/// a request is partway through, that is reported as `RequestTimeout` so the
+/// caller can answer with `408 Request Timeout`.
+async fn read_request<R: AsyncReadExt + std::marker::Unpin>(stream: &mut R, idle_timeout: Duration) -> Result<Request, ReadRequestError> {
+    const BUFFER_SIZE: usize = 1024;
+
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; BUFFER_SIZE];
+
+    let header_end = loop {
+        if let Some(pos) = find_subslice(&buf, b"\r\n\r\n") {
+            break pos + 4;
+        }
+
+        let n = match tokio::time::timeout(idle_timeout, stream.read(&mut chunk)).await {
+            Ok(result) => result.map_err(ReadRequestError::Io)?,
+            Err(_) if buf.is_empty() => return Err(ReadRequestError::IdleTimeout),
+            Err(_) => return Err(ReadRequestError::RequestTimeout),
+        };
+        if n == 0 {
+            return Err(ReadRequestError::ConnectionClosed);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+
+    let head = std::str::from_utf8(&buf[..header_end])
+        .map_err(|_| ReadRequestError::BadRequest("Request head is not valid UTF-8"))?;
+
+    let mut lines = head.lines();
+    let (method, path, version) = lines.next()
+        .and_then(|line| line.find(' ').map(|i| (line, i)))
+        .and_then(|(line, idx1)| match (idx1, line.rfind(' ')) {
+            (idx1, Some(idx2)) if idx1 != idx2 => Some((line[..idx1].trim(), line[idx1+1..idx2].trim(), line[idx2+1..].trim())),
+            (_, _) => None,
+        })
+        .ok_or(ReadRequestError::BadRequest("Malformed request line"))?;
+
+    let method = HttpMethod::try_from(method)
+        .map_err(|_| ReadRequestError::BadRequest("Unknown HTTP method"))?;
+
+    let headers = headers_to_map(lines.filter_map(split_header).collect());
+
+    let content_length = match headers.get("Content-Length") {
+        Some(value) => Some(
+            value.first().trim().parse::<usize>()
+                .map_err(|_| ReadRequestError::BadRequest("Invalid Content-Length"))?
+        ),
+        None => None,
+    };
+
+    let mut body = buf[header_end..].to_vec();
+
+    if let Some(content_length) = content_length {
+        if body.len() < content_length {
+            let remaining = content_length - body.len();
+            let mut remaining_buf = vec![0u8; remaining];
+            tokio::time::timeout(idle_timeout, stream.read_exact(&mut remaining_buf)).await
+                .map_err(|_| ReadRequestError::RequestTimeout)?
+                .map_err(ReadRequestError::Io)?;
+            body.extend_from_slice(&remaining_buf);
+        } else {
+            body.truncate(content_length);
+        }
+    }
+
+    Ok(Request {
+        method,
+        path: path.to_string(),
+        http_version: version.to_string(),
+        headers,
+        body,
+    })
+}
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Howard Hinnant's `civil_from_days`/`days_from_civil` algorithms, used to
+/// format and parse the RFC 7231 IMF-fixdate (e.g. `Last-Modified`,
+/// `If-Modified-Since`) without pulling in a date/time dependency.
+fn civil_from_days(z: i64) -> (i64, u32, u32) {
+    let z = z + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = (z - era * 146097) as u64;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let y = yoe as i64 + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = if m <= 2 { y + 1 } else { y };
+    (y, m, d)
+}
+
+fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = (y - era * 400) as u64;
+    let month_index = if m > 2 { m - 3 } else { m + 9 } as u64;
+    let doy = (153 * month_index + 2) / 5 + d as u64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe as i64 - 719468
+}
+
+fn format_http_date(unix_time: u64) -> String {
+    let days = (unix_time / 86400) as i64;
+    let secs_of_day = unix_time % 86400;
+    let (hour, minute, second) = (secs_of_day / 3600, (secs_of_day % 3600) / 60, secs_of_day % 60);
+
+    // 1970-01-01 was a Thursday.
+    let weekday = WEEKDAYS[((days % 7 + 7 + 4) % 7) as usize];
+    let (year, month, day) = civil_from_days(days);
+
+    format!("{weekday}, {day:02} {} {year} {hour:02}:{minute:02}:{second:02} GMT", MONTHS[(month - 1) as usize])
+}
+
+/// Parses the subset of RFC 7231 dates this server ever emits itself
+/// (IMF-fixdate, e.g. `Tue, 15 Nov 1994 08:12:31 GMT`).
+fn parse_http_date(s: &str) -> Option<u64> {
+    let (_, rest) = s.trim().split_once(", ")?;
+    let mut parts = rest.split_whitespace();
+
+    let day: u32 = parts.next()?.parse().ok()?;
+    let month_str = parts.next()?;
+    let month = MONTHS.iter().position(|m| *m == month_str)? as u32 + 1;
+    let year: i64 = parts.next()?.parse().ok()?;
+
+    let mut time = parts.next()?.split(':');
+    let hour: u64 = time.next()?.parse().ok()?;
+    let minute: u64 = time.next()?.parse().ok()?;
+    let second: u64 = time.next()?.parse().ok()?;
+
+    if parts.next()? != "GMT" {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    Some((days * 86400) as u64 + hour * 3600 + minute * 60 + second)
+}
+
+#[cfg(test)]
+mod http_date_tests {
+    use super::{format_http_date, parse_http_date};
+
+    #[test]
+    fn formats_known_timestamp() {
+        // 1994-11-15T08:12:31Z, the RFC 7231 example date.
+        assert_eq!(format_http_date(784887151), "Tue, 15 Nov 1994 08:12:31 GMT");
+    }
+
+    #[test]
+    fn formats_unix_epoch() {
+        assert_eq!(format_http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+    }
+
+    #[test]
+    fn parses_known_date() {
+        assert_eq!(parse_http_date("Tue, 15 Nov 1994 08:12:31 GMT"), Some(784887151));
+    }
+
+    #[test]
+    fn rejects_malformed_date() {
+        assert_eq!(parse_http_date("not a date"), None);
+        assert_eq!(parse_http_date("Tue, 15 Nov 1994 08:12:31 EST"), None);
+    }
+
+    #[test]
+    fn round_trips_through_format_and_parse() {
+        for timestamp in [0, 86399, 784887151, 4102444800] {
+            assert_eq!(parse_http_date(&format_http_date(timestamp)), Some(timestamp));
+        }
+    }
+}
+
+fn content_type_for_path(path: &str) -> &'static str {
+    let extension = path.rsplit('.').next().unwrap_or("").to_ascii_lowercase();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" => "text/javascript",
+        "json" => "application/json",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "txt" => "text/plain",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// A parsed `Range: bytes=...` request header, not yet resolved against the
+/// file's actual size.
+enum ByteRange {
+    FromTo(u64, u64),
+    From(u64),
+    Suffix(u64),
+}
+
+fn parse_range(header: &str) -> Option<ByteRange> {
+    let (start, end) = header.strip_prefix("bytes=")?.split_once('-')?;
+
+    match (start.is_empty(), end.is_empty()) {
+        (false, false) => Some(ByteRange::FromTo(start.parse().ok()?, end.parse().ok()?)),
+        (false, true) => Some(ByteRange::From(start.parse().ok()?)),
+        (true, false) => Some(ByteRange::Suffix(end.parse().ok()?)),
+        (true, true) => None,
+    }
+}
+
+/// Resolves a `ByteRange` against the file's total size, returning the
+/// inclusive `(start, end)` byte offsets, or `None` if the range lies
+/// entirely outside the file.
+fn resolve_range(range: ByteRange, total: u64) -> Option<(u64, u64)> {
+    if total == 0 {
+        return None;
+    }
+
+    let (start, end) = match range {
+        ByteRange::FromTo(start, end) => (start, end.min(total - 1)),
+        ByteRange::From(start) => (start, total - 1),
+        ByteRange::Suffix(len) if len >= total => (0, total - 1),
+        ByteRange::Suffix(len) => (total - len, total - 1),
+    };
+
+    if start >= total || start > end {
+        None
+    } else {
+        Some((start, end))
+    }
+}
+
+#[cfg(test)]
+mod range_tests {
+    use super::{parse_range, resolve_range, ByteRange};
+
+    #[test]
+    fn parses_from_to() {
+        assert!(matches!(parse_range("bytes=0-499"), Some(ByteRange::FromTo(0, 499))));
+    }
+
+    #[test]
+    fn parses_from_only() {
+        assert!(matches!(parse_range("bytes=500-"), Some(ByteRange::From(500))));
+    }
+
+    #[test]
+    fn parses_suffix() {
+        assert!(matches!(parse_range("bytes=-500"), Some(ByteRange::Suffix(500))));
+    }
+
+    #[test]
+    fn rejects_multi_range_and_malformed_headers() {
+        // Multiple ranges aren't supported; `split_once('-')` splits at the
+        // first `-` only, leaving "10,20-30" as the end, which isn't a number.
+        assert!(parse_range("bytes=0-10,20-30").is_none());
+        assert!(parse_range("bytes=").is_none());
+        assert!(parse_range("items=0-10").is_none());
+    }
+
+    #[test]
+    fn resolves_from_to_clamped_to_file_size() {
+        assert_eq!(resolve_range(ByteRange::FromTo(0, 499), 1000), Some((0, 499)));
+        assert_eq!(resolve_range(ByteRange::FromTo(900, 999_999), 1000), Some((900, 999)));
+    }
+
+    #[test]
+    fn resolves_from_to_end_of_file() {
+        assert_eq!(resolve_range(ByteRange::From(990), 1000), Some((990, 999)));
+    }
+
+    #[test]
+    fn resolves_suffix_ranges() {
+        assert_eq!(resolve_range(ByteRange::Suffix(500), 1000), Some((500, 999)));
+        // A suffix longer than the file just means "the whole file".
+        assert_eq!(resolve_range(ByteRange::Suffix(5000), 1000), Some((0, 999)));
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_start() {
+        assert_eq!(resolve_range(ByteRange::From(1000), 1000), None);
+        assert_eq!(resolve_range(ByteRange::FromTo(1000, 1010), 1000), None);
+    }
+
+    #[test]
+    fn rejects_any_range_on_empty_file() {
+        assert_eq!(resolve_range(ByteRange::FromTo(0, 0), 0), None);
+        assert_eq!(resolve_range(ByteRange::Suffix(0), 0), None);
+    }
+}
+
+/// Resolves `filename` (a path captured straight from the URL) against
+/// `dir`, rejecting any `..` component so a request can't escape
+/// `--directory` to read or write files elsewhere on disk.
+fn resolve_file_path(dir: &str, filename: &str) -> Option<PathBuf> {
+    if filename.split('/').any(|segment| segment == "..") {
+        return None;
+    }
+
+    Some(PathBuf::from(format!("{dir}{filename}")))
+}
+
+async fn send_file(request: &Request, filename: &str, dir: &str) -> Result<Response> {
+    let Some(file_path) = resolve_file_path(dir, filename) else {
+        return Ok(Response::from(HttpStatusCode::BadRequest));
+    };
+
+    let metadata = match tokio::fs::metadata(&file_path).await {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Response::from(HttpStatusCode::NotFound)),
+        Err(e) => return Err(e.into()),
+    };
+    let total = metadata.len();
+    let mtime = metadata.modified()?.duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+
+    let etag = format!("\"{total:x}-{mtime:x}\"");
+    let last_modified = format_http_date(mtime);
+
+    let not_modified = match request.headers.get("If-None-Match") {
+        Some(if_none_match) => {
+            let value = if_none_match.first().trim();
+            value == "*" || value == etag
+        }
+        None => match request.headers.get("If-Modified-Since") {
+            Some(if_modified_since) => parse_http_date(if_modified_since.first()).is_some_and(|since| mtime <= since),
+            None => false,
+        },
+    };
+
+    if not_modified {
+        return Ok(Response::new_without_body(
+            HttpStatusCode::NotModified,
+            vec![("ETag".to_string(), etag), ("Last-Modified".to_string(), last_modified)],
+        ));
+    }
+
+    let common_headers = vec![
+        ("Content-Type".to_string(), content_type_for_path(filename).to_string()),
+        ("ETag".to_string(), etag),
+        ("Last-Modified".to_string(), last_modified),
+        ("Accept-Ranges".to_string(), "bytes".to_string()),
+    ];
+
+    if let Some(range_header) = request.headers.get("Range") {
+        let range = match parse_range(range_header.first()) {
+            Some(range) => range,
+            None => return Ok(Response::new_without_body(HttpStatusCode::RangeNotSatisfiable, common_headers)),
+        };
+
+        let (start, end) = match resolve_range(range, total) {
+            Some(bounds) => bounds,
+            None => {
+                let mut headers = common_headers;
+                headers.push(("Content-Range".to_string(), format!("bytes */{total}")));
+                return Ok(Response::new_without_body(HttpStatusCode::RangeNotSatisfiable, headers));
+            }
+        };
+
+        let mut file = File::open(&file_path).await?;
+        file.seek(io::SeekFrom::Start(start)).await?;
+
+        let mut slice = vec![0u8; (end - start + 1) as usize];
+        file.read_exact(&mut slice).await?;
+
+        let mut headers = common_headers;
+        headers.push(("Content-Range".to_string(), format!("bytes {start}-{end}/{total}")));
+
+        return Ok(Response::new(HttpStatusCode::PartialContent, headers, slice));
+    }
+
+    // Stream the file instead of buffering it whole so large downloads don't
+    // have to fit in memory.
+    let file = File::open(&file_path).await?;
+    Ok(Response::new_streamed(HttpStatusCode::Ok, common_headers, ReaderStream::new(file)))
+}
 
-                    let response = Response::new(
+async fn save_file(filename: &str, dir: &str, body: &[u8]) -> Result<Response> {
+    let Some(file_path) = resolve_file_path(dir, filename) else {
+        return Ok(Response::from(HttpStatusCode::BadRequest));
+    };
+
+    let mut file = File::create(file_path).await?;
+    file.write_all(body).await?;
+
+    Ok(Response::from(HttpStatusCode::Created))
+}
+
+fn build_router(dir: Arc<Option<String>>) -> Router {
+    let mut router = Router::new(CorsConfig::default());
+
+    router.add(HttpMethod::GET, RouteDefinition {
+        matches: |path| if path == "/" { Some(()) } else { None },
+        action: |_request, _matches| async { Ok(Response::from(HttpStatusCode::Ok)) },
+    });
+
+    router.add(HttpMethod::GET, RouteDefinition {
+        matches: |path| if path == "/user-agent" { Some(()) } else { None },
+        action: |request, _matches| async move {
+            match request.headers.get("User-Agent") {
+                Some(user_agent) => {
+                    let user_agent = user_agent.first();
+
+                    Ok(Response::new(
                         HttpStatusCode::Ok,
                         vec![
                             ("Content-Type".to_string(), "text/plain".to_string()),
-                            ("Content-Length".to_string(), len),
+                            ("Content-Length".to_string(), format!("{}", user_agent.len())),
                         ],
-                        message.to_string()
-                    );
-                    
-                    response.write_to(&mut stream).await;
+                        user_agent.to_string().into_bytes(),
+                    ))
                 }
-                _ if path.starts_with("/files/") => {
+                None => Ok(Response::from(HttpStatusCode::NotFound)),
+            }
+        },
+    });
+
+    router.add(HttpMethod::GET, RouteDefinition {
+        matches: |path| match_pattern("/echo/:message*", path),
+        action: |_request, captures| async move {
+            let message = captures.get("message").cloned().unwrap_or_default();
+
+            Ok(Response::new(
+                HttpStatusCode::Ok,
+                vec![
+                    ("Content-Type".to_string(), "text/plain".to_string()),
+                    ("Content-Length".to_string(), format!("{}", message.len())),
+                ],
+                message.into_bytes(),
+            ))
+        },
+    });
+
+    {
+        let dir = dir.clone();
+        router.add(HttpMethod::GET, RouteDefinition {
+            matches: |path| match_pattern("/files/:filename*", path),
+            action: move |request, captures| {
+                let dir = dir.clone();
+                async move {
+                    match dir.as_ref() {
+                        Some(dir) => send_file(&request, &captures["filename"], dir).await,
+                        None => Ok(Response::from(HttpStatusCode::NotFound)),
+                    }
+                }
+            },
+        });
+    }
+
+    {
+        let dir = dir.clone();
+        router.add(HttpMethod::POST, RouteDefinition {
+            matches: |path| match_pattern("/files/:filename*", path),
+            action: move |request, captures| {
+                let dir = dir.clone();
+                async move {
                     match dir.as_ref() {
-                        Some(dir) => {
-                            if method == HttpMethod::GET {
-                                send_file(&mut stream, path, dir).await;
-                            } else if method == HttpMethod::POST {
-                                save_file(&mut stream, path, dir, body).await;
-                            }
+                        Some(dir) => save_file(&captures["filename"], dir, &request.body).await,
+                        None => Ok(Response::from(HttpStatusCode::NotFound)),
+                    }
+                }
+            },
+        });
+    }
+
+    router.add_ws(WsRouteDefinition {
+        matches: |path| if path == "/ws" { Some(()) } else { None },
+        action: |mut ws, _matches| async move {
+            while let Some(message) = ws.recv().await? {
+                ws.send(message).await?;
+            }
+            Ok(())
+        },
+    });
+
+    router
+}
+
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// HTTP/1.1 defaults to persistent connections, HTTP/1.0 defaults to closing
+/// after one response; either can be overridden by an explicit `Connection`
+/// header.
+fn should_keep_alive(request: &Request) -> bool {
+    match request.headers.get("Connection").map(|v| v.first().to_ascii_lowercase()) {
+        Some(value) if value == "close" => false,
+        Some(value) if value == "keep-alive" => true,
+        _ => request.http_version.eq_ignore_ascii_case("HTTP/1.1"),
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, router: Arc<Router>) {
+    loop {
+        match read_request(&mut stream, IDLE_TIMEOUT).await {
+            Ok(request) => {
+                println!("Method: {}, Path: {}, Version: {}", request.method, request.path, request.http_version);
+
+                if is_websocket_upgrade(&request) {
+                    if let Some(route) = router.find_ws_route(&request.path) {
+                        let key = request.headers.get("Sec-WebSocket-Key").map_or("", |v| v.first());
+                        let accept = websocket_accept_key(key);
+
+                        let response = Response::new_without_body(
+                            HttpStatusCode::SwitchingProtocols,
+                            vec![
+                                ("Upgrade".to_string(), "websocket".to_string()),
+                                ("Connection".to_string(), "Upgrade".to_string()),
+                                ("Sec-WebSocket-Accept".to_string(), accept),
+                            ],
+                        );
+
+                        if response.write_to(&mut stream).await.is_err() {
+                            return;
                         }
-                        None => {
-                            Response::from(HttpStatusCode::NotFound).write_to(&mut stream).await;
+
+                        let path = request.path.clone();
+                        let ws = WebSocket { stream };
+                        if let Err(e) = (route.run)(&path, ws).await {
+                            eprintln!("WebSocket handler error: {e}");
                         }
+                        return;
                     }
                 }
-                _ => {
-                    Response::from(HttpStatusCode::NotFound).write_to(&mut stream).await;
+
+                let keep_alive = should_keep_alive(&request);
+                let mut response = router.dispatch(request).await;
+                response.headers.push((
+                    "Connection".to_string(),
+                    (if keep_alive { "keep-alive" } else { "close" }).to_string(),
+                ));
+
+                if response.write_to(&mut stream).await.is_err() || !keep_alive {
+                    return;
                 }
             }
-        },
-        Err(e) => println!("Error reading Data: {e}"),
+            Err(ReadRequestError::BadRequest(reason)) => {
+                println!("Invalid Request: {reason}");
+                let _ = Response::from(HttpStatusCode::BadRequest).write_to(&mut stream).await;
+                return;
+            }
+            Err(ReadRequestError::RequestTimeout) => {
+                let _ = Response::from(HttpStatusCode::RequestTimeout).write_to(&mut stream).await;
+                return;
+            }
+            Err(ReadRequestError::IdleTimeout) => return,
+            Err(ReadRequestError::ConnectionClosed) => return,
+            Err(e) => {
+                println!("Error reading Data: {e}");
+                return;
+            }
+        }
     }
 }
 
@@ -368,7 +1522,7 @@ async fn main_loop() -> io::Result<()> {
         .iter()
         .position(|arg| arg == "--directory")
         .and_then(|idx| args.get(idx + 1).cloned())
-        .map(|dir| 
+        .map(|dir|
             if !dir.ends_with("/") {
                 format!("{dir}/")
             } else {
@@ -376,18 +1530,18 @@ async fn main_loop() -> io::Result<()> {
             }
         );
 
-    let arc = Arc::new(dir);
+    let router = Arc::new(build_router(Arc::new(dir)));
 
     let listener = TcpListener::bind("127.0.0.1:4221").await.unwrap();
 
     loop {
         let (socket, socket_addr) = listener.accept().await?;
-        
+
         println!("New Connection from {socket_addr}");
 
-        let dir_ref = arc.clone();
+        let router_ref = router.clone();
         tokio::spawn(async move {
-            handle_connection(socket, dir_ref).await
+            handle_connection(socket, router_ref).await
         });
     }
 }